@@ -1,3 +1,7 @@
+use crate::filter::Filter;
+use crate::format::OutputFormat;
+use regex::Regex;
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 /// A midi event sink that prints the event so stdout.
@@ -10,4 +14,28 @@ pub struct Opt {
     /// widget when it appears.
     #[structopt(long = "jack-name", default_value = "jack-midi-sink")]
     pub jack_name: String,
+    /// Also register a `thru` output port and write a copy of each received event back
+    /// out through it, turning this from a plain sink into a MIDI monitor/router.
+    #[structopt(long = "forward", alias = "thru")]
+    pub forward: bool,
+    /// Restrict which events `--forward` copies through, e.g. `ch1,+note,+cc,-clock`.
+    /// Ignored unless `--forward` is also given. See `Filter`'s `FromStr` impl for the
+    /// full mini-language.
+    #[structopt(long = "filter")]
+    pub filter: Option<Filter>,
+    /// Record everything received at `sink` to a type-1 Standard MIDI File at this
+    /// path. The file is written out when the program shuts down.
+    #[structopt(long = "record")]
+    pub record: Option<PathBuf>,
+    /// Automatically connect any JACK MIDI output port whose name matches this regex to
+    /// our `sink` port, both on startup and whenever a new port appears later (e.g.
+    /// when a source client restarts), mirroring how LADISH reconnects `--jack-name`.
+    /// When the JACK server itself shuts down, the program exits.
+    #[structopt(long = "connect")]
+    pub connect: Option<Regex>,
+    /// Also emit one line of newline-delimited JSON or CSV per event to stdout, carrying
+    /// the frame timestamp, channel, decoded message kind and parameters, and raw hex
+    /// bytes. Diagnostic `log` output always stays on stderr regardless of this setting.
+    #[structopt(long = "format", default_value = "text")]
+    pub format: OutputFormat,
 }
@@ -0,0 +1,19 @@
+use jack::{Client, PortFlags};
+use regex::Regex;
+
+/// Find every JACK MIDI output port whose name matches `pattern` and connect it to our
+/// `sink_name` input port.
+///
+/// Connecting an already-connected pair of ports is a harmless no-op in JACK, so this is
+/// safe to call repeatedly (on startup, and again every time a new port appears).
+pub fn connect_matching(client: &Client, sink_name: &str, pattern: &Regex) {
+    for port_name in client.ports(None, Some("midi"), PortFlags::IS_OUTPUT) {
+        if !pattern.is_match(&port_name) {
+            continue;
+        }
+        match client.connect_ports_by_name(&port_name, sink_name) {
+            Ok(()) => log::info!("connected {} to {}", port_name, sink_name),
+            Err(err) => log::debug!("could not connect {} to {}: {}", port_name, sink_name, err),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Max size in bytes of a single raw MIDI event we carry across the ring buffer.
+///
+/// Ordinary channel messages are at most 3 bytes; SysEx dumps are reassembled on the
+/// realtime thread by [`crate::sysex::SysExAccumulator`] and handed over as
+/// [`ConsumerEvent::SysEx`], so they never need to fit in a `RawEvent`.
+pub const MAX_EVENT_BYTES: usize = 256;
+
+/// A MIDI event captured on the realtime thread, ready to hand to the consumer thread.
+///
+/// Stored as a fixed-size buffer so that pushing an event into the ring buffer never
+/// allocates.
+#[derive(Clone, Copy)]
+pub struct RawEvent {
+    pub time: u32,
+    len: u16,
+    data: [u8; MAX_EVENT_BYTES],
+}
+
+impl RawEvent {
+    /// Build an event from the bytes JACK gave us. Returns `None` if the event doesn't fit
+    /// in [`MAX_EVENT_BYTES`], in which case the caller should count it as dropped.
+    pub fn from_bytes(time: u32, bytes: &[u8]) -> Option<Self> {
+        if bytes.len() > MAX_EVENT_BYTES {
+            return None;
+        }
+        let mut data = [0u8; MAX_EVENT_BYTES];
+        data[..bytes.len()].copy_from_slice(bytes);
+        Some(RawEvent {
+            time,
+            len: bytes.len() as u16,
+            data,
+        })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data[..self.len as usize]
+    }
+}
+
+/// An item handed from the realtime thread to the consumer thread over the ring buffer.
+pub enum ConsumerEvent {
+    /// A single, already-complete event.
+    Raw(RawEvent),
+    /// A SysEx message reassembled by [`crate::sysex::SysExAccumulator`] out of one or
+    /// more realtime events.
+    SysEx { time: u32, message: Vec<u8> },
+}
+
+/// A dropped-event counter shared between the realtime thread and the consumer thread.
+///
+/// The realtime side only ever increments it; the consumer side periodically takes the
+/// count and logs it, so a full ring buffer never causes the audio thread to block.
+#[derive(Clone)]
+pub struct DroppedCounter(Arc<AtomicU64>);
+
+impl DroppedCounter {
+    pub fn new() -> Self {
+        DroppedCounter(Arc::new(AtomicU64::new(0)))
+    }
+
+    pub fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Take the current count and reset it to zero.
+    pub fn take(&self) -> u64 {
+        self.0.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_bytes_round_trips() {
+        let event = RawEvent::from_bytes(42, &[0x90, 0x40, 0x7f]).unwrap();
+        assert_eq!(event.time, 42);
+        assert_eq!(event.bytes(), &[0x90, 0x40, 0x7f]);
+    }
+
+    #[test]
+    fn from_bytes_accepts_exactly_max_event_bytes() {
+        let bytes = vec![0xF0; MAX_EVENT_BYTES];
+        let event = RawEvent::from_bytes(0, &bytes).unwrap();
+        assert_eq!(event.bytes(), bytes.as_slice());
+        assert_eq!(event.bytes().len(), MAX_EVENT_BYTES);
+    }
+
+    #[test]
+    fn from_bytes_rejects_oversized_events() {
+        let bytes = vec![0xF0; MAX_EVENT_BYTES + 1];
+        assert!(RawEvent::from_bytes(0, &bytes).is_none());
+    }
+
+    #[test]
+    fn from_bytes_handles_empty_slice() {
+        let event = RawEvent::from_bytes(0, &[]).unwrap();
+        assert_eq!(event.bytes(), &[] as &[u8]);
+    }
+
+    #[test]
+    fn dropped_counter_take_resets_to_zero() {
+        let dropped = DroppedCounter::new();
+        assert_eq!(dropped.take(), 0);
+        dropped.increment();
+        dropped.increment();
+        assert_eq!(dropped.take(), 2);
+        assert_eq!(dropped.take(), 0);
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Which events `--forward` copies through to the `thru` output port.
+///
+/// Parsed from a comma-separated list of tokens:
+///
+/// - `chN` only pass channel-voice events on channel `N` (1-based); may be repeated to
+///   allow several channels.
+/// - `+kind` only pass events of `kind` (may be repeated); with no `+kind` token every
+///   kind is allowed unless excluded below.
+/// - `-kind` always drop events of `kind`, regardless of the rules above.
+///
+/// `kind` is one of `note`, `poly-pressure`, `cc`, `program`, `channel-pressure`,
+/// `pitch-bend`, `sysex`, `clock`, `start`, `continue`, `stop`, `active-sensing`,
+/// `reset`.
+///
+/// For example `ch1,+note,+cc,-clock` keeps only note and CC events on channel 1, and
+/// always drops the MIDI clock regardless of channel.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    channels: Option<HashSet<u8>>,
+    include: Option<HashSet<MessageKind>>,
+    exclude: HashSet<MessageKind>,
+}
+
+impl Filter {
+    /// Should an event of this `kind`, on this `channel` (if it has one), be forwarded?
+    pub fn allows(&self, kind: MessageKind, channel: Option<u8>) -> bool {
+        if self.exclude.contains(&kind) {
+            return false;
+        }
+        if let Some(include) = &self.include {
+            if !include.contains(&kind) {
+                return false;
+            }
+        }
+        if let (Some(channels), Some(channel)) = (&self.channels, channel) {
+            if !channels.contains(&channel) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut filter = Filter::default();
+        for token in spec.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            if let Some(rest) = token.strip_prefix("ch") {
+                let channel: u8 = rest
+                    .parse()
+                    .map_err(|_| format!("invalid channel in filter token {:?}", token))?;
+                filter
+                    .channels
+                    .get_or_insert_with(HashSet::new)
+                    .insert(channel);
+            } else if let Some(kind) = token.strip_prefix('+') {
+                filter
+                    .include
+                    .get_or_insert_with(HashSet::new)
+                    .insert(kind.parse()?);
+            } else if let Some(kind) = token.strip_prefix('-') {
+                filter.exclude.insert(kind.parse()?);
+            } else {
+                return Err(format!(
+                    "unrecognised filter token {:?}, expected ch<N>, +<kind> or -<kind>",
+                    token
+                ));
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// The broad category of a MIDI message, used by [`Filter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    Note,
+    PolyPressure,
+    Cc,
+    ProgramChange,
+    ChannelPressure,
+    PitchBend,
+    SysEx,
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    Reset,
+    Other,
+}
+
+impl FromStr for MessageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "note" => MessageKind::Note,
+            "poly-pressure" => MessageKind::PolyPressure,
+            "cc" => MessageKind::Cc,
+            "program" => MessageKind::ProgramChange,
+            "channel-pressure" => MessageKind::ChannelPressure,
+            "pitch-bend" => MessageKind::PitchBend,
+            "sysex" => MessageKind::SysEx,
+            "clock" => MessageKind::Clock,
+            "start" => MessageKind::Start,
+            "continue" => MessageKind::Continue,
+            "stop" => MessageKind::Stop,
+            "active-sensing" => MessageKind::ActiveSensing,
+            "reset" => MessageKind::Reset,
+            other => return Err(format!("unrecognised message kind {:?}", other)),
+        })
+    }
+}
+
+/// Classify a raw MIDI message by its status byte.
+///
+/// `nom_midi`'s parser only understands channel voice messages, so this classifies from
+/// the raw bytes rather than a parsed `MidiEventType`, and works equally well for
+/// channel voice, system and real-time messages.
+///
+/// `in_sysex` should be `true` when this event arrives while a `SysExAccumulator` has a
+/// message in progress. Continuation bytes (and the `0xF7` terminator) don't start with
+/// a status byte of their own, so without this they'd otherwise classify as `Other`.
+pub fn classify(bytes: &[u8], in_sysex: bool) -> MessageKind {
+    match bytes.first() {
+        Some(&b) if in_sysex && (b < 0x80 || b == 0xF7) => MessageKind::SysEx,
+        Some(&b) if (0x80..=0x9F).contains(&b) => MessageKind::Note,
+        Some(&b) if (0xA0..=0xAF).contains(&b) => MessageKind::PolyPressure,
+        Some(&b) if (0xB0..=0xBF).contains(&b) => MessageKind::Cc,
+        Some(&b) if (0xC0..=0xCF).contains(&b) => MessageKind::ProgramChange,
+        Some(&b) if (0xD0..=0xDF).contains(&b) => MessageKind::ChannelPressure,
+        Some(&b) if (0xE0..=0xEF).contains(&b) => MessageKind::PitchBend,
+        Some(&0xF0) => MessageKind::SysEx,
+        Some(&0xF8) => MessageKind::Clock,
+        Some(&0xFA) => MessageKind::Start,
+        Some(&0xFB) => MessageKind::Continue,
+        Some(&0xFC) => MessageKind::Stop,
+        Some(&0xFE) => MessageKind::ActiveSensing,
+        Some(&0xFF) => MessageKind::Reset,
+        _ => MessageKind::Other,
+    }
+}
+
+/// The 1-based MIDI channel a channel-voice message targets, or `None` for system and
+/// real-time messages.
+pub fn channel(bytes: &[u8]) -> Option<u8> {
+    bytes
+        .first()
+        .filter(|&&b| (0x80..=0xEF).contains(&b))
+        .map(|&b| (b & 0x0F) + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_filter_allows_everything() {
+        let filter = Filter::default();
+        assert!(filter.allows(MessageKind::Note, Some(1)));
+        assert!(filter.allows(MessageKind::SysEx, None));
+    }
+
+    #[test]
+    fn parses_channel_tokens() {
+        let filter: Filter = "ch1,ch3".parse().unwrap();
+        assert!(filter.allows(MessageKind::Note, Some(1)));
+        assert!(filter.allows(MessageKind::Note, Some(3)));
+        assert!(!filter.allows(MessageKind::Note, Some(2)));
+        // Events with no channel (system/real-time) aren't restricted by ch<N>.
+        assert!(filter.allows(MessageKind::Clock, None));
+    }
+
+    #[test]
+    fn include_token_restricts_to_listed_kinds() {
+        let filter: Filter = "+note,+cc".parse().unwrap();
+        assert!(filter.allows(MessageKind::Note, None));
+        assert!(filter.allows(MessageKind::Cc, None));
+        assert!(!filter.allows(MessageKind::ProgramChange, None));
+    }
+
+    #[test]
+    fn exclude_token_wins_even_if_included() {
+        let filter: Filter = "+note,-note".parse().unwrap();
+        assert!(!filter.allows(MessageKind::Note, None));
+    }
+
+    #[test]
+    fn invalid_channel_token_is_an_error() {
+        assert!("chfoo".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn unrecognised_token_is_an_error() {
+        assert!("bogus".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn unrecognised_kind_is_an_error() {
+        assert!("+bogus".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn classify_channel_voice_messages() {
+        assert_eq!(classify(&[0x90, 0x40, 0x7f], false), MessageKind::Note);
+        assert_eq!(classify(&[0xA0, 0x40, 0x7f], false), MessageKind::PolyPressure);
+        assert_eq!(classify(&[0xB0, 0x07, 0x7f], false), MessageKind::Cc);
+        assert_eq!(classify(&[0xC0, 0x01], false), MessageKind::ProgramChange);
+        assert_eq!(classify(&[0xD0, 0x40], false), MessageKind::ChannelPressure);
+        assert_eq!(classify(&[0xE0, 0x00, 0x40], false), MessageKind::PitchBend);
+    }
+
+    #[test]
+    fn classify_system_and_realtime_messages() {
+        assert_eq!(classify(&[0xF0], false), MessageKind::SysEx);
+        assert_eq!(classify(&[0xF8], false), MessageKind::Clock);
+        assert_eq!(classify(&[0xFA], false), MessageKind::Start);
+        assert_eq!(classify(&[0xFB], false), MessageKind::Continue);
+        assert_eq!(classify(&[0xFC], false), MessageKind::Stop);
+        assert_eq!(classify(&[0xFE], false), MessageKind::ActiveSensing);
+        assert_eq!(classify(&[0xFF], false), MessageKind::Reset);
+        assert_eq!(classify(&[0xF1], false), MessageKind::Other);
+        assert_eq!(classify(&[], false), MessageKind::Other);
+    }
+
+    #[test]
+    fn classify_sysex_continuation_bytes_only_when_in_sysex() {
+        // Without in_sysex, a data byte with no status byte classifies as Other.
+        assert_eq!(classify(&[0x10, 0x20], false), MessageKind::Other);
+        // With in_sysex, the same bytes (and the 0xF7 terminator) are SysEx.
+        assert_eq!(classify(&[0x10, 0x20], true), MessageKind::SysEx);
+        assert_eq!(classify(&[0xF7], true), MessageKind::SysEx);
+        // Real-time bytes still classify normally even mid-SysEx.
+        assert_eq!(classify(&[0xF8], true), MessageKind::Clock);
+    }
+
+    #[test]
+    fn channel_extracts_1_based_channel_for_voice_messages() {
+        assert_eq!(channel(&[0x90, 0x40, 0x7f]), Some(1));
+        assert_eq!(channel(&[0x9F, 0x40, 0x7f]), Some(16));
+        assert_eq!(channel(&[0xF0]), None);
+        assert_eq!(channel(&[]), None);
+    }
+}
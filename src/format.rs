@@ -0,0 +1,193 @@
+use crate::filter::MessageKind;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Output format for the structured per-event stream written to stdout, selected with
+/// `--format`. `Text` is the default and changes nothing: the existing human-readable
+/// `log::info!` lines (on stderr) already cover that case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Text
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            other => Err(format!(
+                "unrecognised format {:?}, expected text, json or csv",
+                other
+            )),
+        }
+    }
+}
+
+/// One parsed event, borrowed just long enough to be written out. Building and writing
+/// this happens on the consumer thread, as part of the same logging step that already
+/// moved off the realtime thread.
+pub struct Record<'a> {
+    pub time: u32,
+    pub channel: Option<u8>,
+    pub kind: MessageKind,
+    pub data: &'a [u8],
+    pub raw: &'a [u8],
+}
+
+impl Record<'_> {
+    /// Write this record to `out` in `format`. A no-op for `OutputFormat::Text`.
+    pub fn write(&self, format: OutputFormat, out: &mut impl Write) -> io::Result<()> {
+        match format {
+            OutputFormat::Text => Ok(()),
+            OutputFormat::Json => writeln!(out, "{}", self.to_json()),
+            OutputFormat::Csv => writeln!(out, "{}", self.to_csv()),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"time\":{},\"channel\":{},\"kind\":\"{:?}\",\"data\":[{}],\"raw\":\"{}\"}}",
+            self.time,
+            self.channel
+                .map(|c| c.to_string())
+                .unwrap_or_else(|| "null".to_string()),
+            self.kind,
+            join(self.data, ","),
+            hex(self.raw),
+        )
+    }
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{:?},{},{}",
+            self.time,
+            self.channel.map(|c| c.to_string()).unwrap_or_default(),
+            self.kind,
+            join(self.data, " "),
+            hex(self.raw),
+        )
+    }
+}
+
+fn join(bytes: &[u8], sep: &str) -> String {
+    bytes
+        .iter()
+        .map(u8::to_string)
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_from_str_recognises_all_variants() {
+        assert_eq!("text".parse(), Ok(OutputFormat::Text));
+        assert_eq!("json".parse(), Ok(OutputFormat::Json));
+        assert_eq!("csv".parse(), Ok(OutputFormat::Csv));
+        assert!("bogus".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn text_write_is_a_no_op() {
+        let record = Record {
+            time: 0,
+            channel: None,
+            kind: MessageKind::Clock,
+            data: &[],
+            raw: &[0xF8],
+        };
+        let mut out = Vec::new();
+        record.write(OutputFormat::Text, &mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn json_write_includes_channel_data_and_raw_hex() {
+        let record = Record {
+            time: 42,
+            channel: Some(1),
+            kind: MessageKind::Note,
+            data: &[0x40, 0x7f],
+            raw: &[0x90, 0x40, 0x7f],
+        };
+        let mut out = Vec::new();
+        record.write(OutputFormat::Json, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"time\":42,\"channel\":1,\"kind\":\"Note\",\"data\":[64,127],\"raw\":\"90407f\"}\n"
+        );
+    }
+
+    #[test]
+    fn json_write_uses_null_for_no_channel() {
+        let record = Record {
+            time: 0,
+            channel: None,
+            kind: MessageKind::Clock,
+            data: &[],
+            raw: &[0xF8],
+        };
+        let mut out = Vec::new();
+        record.write(OutputFormat::Json, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"time\":0,\"channel\":null,\"kind\":\"Clock\",\"data\":[],\"raw\":\"f8\"}\n"
+        );
+    }
+
+    #[test]
+    fn csv_write_leaves_channel_blank_for_no_channel() {
+        let record = Record {
+            time: 7,
+            channel: None,
+            kind: MessageKind::Reset,
+            data: &[],
+            raw: &[0xFF],
+        };
+        let mut out = Vec::new();
+        record.write(OutputFormat::Csv, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "7,,Reset,,ff\n");
+    }
+
+    #[test]
+    fn csv_write_includes_channel_and_space_separated_data() {
+        let record = Record {
+            time: 7,
+            channel: Some(16),
+            kind: MessageKind::Cc,
+            data: &[0x07, 0x7f],
+            raw: &[0xBF, 0x07, 0x7f],
+        };
+        let mut out = Vec::new();
+        record.write(OutputFormat::Csv, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "7,16,Cc,7 127,bf077f\n");
+    }
+
+    #[test]
+    fn join_handles_empty_slice() {
+        assert_eq!(join(&[], ","), "");
+    }
+
+    #[test]
+    fn hex_formats_each_byte_as_two_lowercase_digits() {
+        assert_eq!(hex(&[0x00, 0x0a, 0xff]), "000aff");
+        assert_eq!(hex(&[]), "");
+    }
+}
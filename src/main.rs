@@ -1,55 +1,412 @@
 mod cli;
+mod connect;
+mod event;
+mod filter;
+mod format;
+mod smf;
+mod sysex;
 
 use crate::cli::Opt;
-use jack::{Client, Control, MidiIn, Port, ProcessHandler, ProcessScope};
+use crate::event::{ConsumerEvent, DroppedCounter, RawEvent};
+use crate::filter::Filter;
+use crate::format::{OutputFormat, Record};
+use crate::smf::Recorder;
+use crate::sysex::{sysex_buffer_pool, SysExAccumulator, SysExBufferPool, SysExOutcome, SysExStats};
+use jack::{
+    Client, ClientStatus, Control, MidiIn, MidiOut, NotificationHandler, Port, PortId,
+    ProcessHandler, ProcessScope, RawMidi as JackRawMidi,
+};
 use nom_midi::{MidiEvent, MidiEventType};
+use regex::Regex;
+use rtrb::{Consumer, Producer, PopError, RingBuffer};
 use std::{
     error::Error as StdError,
     io::{self, BufRead},
+    path::PathBuf,
     str::FromStr,
-    sync::atomic::{AtomicI8, Ordering},
+    sync::atomic::{AtomicBool, AtomicI8, Ordering},
+    sync::Arc,
+    time::{Duration, Instant},
     fmt
 };
 use structopt::StructOpt;
 
+/// Capacity of the SPSC queue between the realtime thread and the consumer thread.
+const RING_BUFFER_CAPACITY: usize = 1024;
+/// How often the consumer thread reports dropped events (if any occurred).
+const DROP_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the consumer thread sleeps when the queue is empty, to avoid busy-waiting.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Largest SysEx message we'll reassemble before abandoning it.
+const MAX_SYSEX_BYTES: usize = 1 << 20;
+/// How many spare SysEx message buffers to preallocate for [`sysex::sysex_buffer_pool`].
+/// The consumer thread normally returns a buffer long before the next message
+/// completes; this only needs to cover several messages completing back-to-back while
+/// the consumer thread is still busy with the previous one.
+const SYSEX_BUFFER_POOL_SIZE: usize = 4;
+
 /// Main programm runner.
 fn run(opts: Opt) -> Result<(), Box<dyn StdError>> {
     let (client, status) = Client::new(&opts.jack_name, jack::ClientOptions::NO_START_SERVER)?;
     log::info!("name: {}", client.name());
-    let ports = Ports::setup(&client)?;
-    let async_client = client.activate_async((), ports)?;
-    let (_tx, rx) = std::sync::mpsc::channel::<()>();
-    rx.recv(); // block forever
+    let sample_rate = client.sample_rate();
+
+    let (producer, consumer) = RingBuffer::new(RING_BUFFER_CAPACITY);
+    let dropped = DroppedCounter::new();
+    let sysex_stats = SysExStats::new();
+    let (sysex_buffer_pool, sysex_free_buffers) =
+        sysex_buffer_pool(SYSEX_BUFFER_POOL_SIZE, MAX_SYSEX_BYTES);
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    let ports = Ports::setup(
+        &client,
+        producer,
+        dropped.clone(),
+        sysex_stats.clone(),
+        sysex_free_buffers,
+        opts.forward,
+        opts.filter.unwrap_or_default(),
+    )?;
+    let sink_name = ports.sink.name()?;
+
+    let (tx, rx) = std::sync::mpsc::channel::<()>();
+    let notifications = Notifications::new(sink_name.clone(), opts.connect.clone(), tx.clone());
+    let async_client = client.activate_async(notifications, ports)?;
+
+    if let Some(pattern) = &opts.connect {
+        connect::connect_matching(async_client.as_client(), &sink_name, pattern);
+    }
+
+    let consumer_shutdown = shutdown.clone();
+    let record_path = opts.record;
+    let format = opts.format;
+    let consumer_thread = std::thread::spawn(move || {
+        consume_events(
+            consumer,
+            dropped,
+            sysex_stats,
+            sysex_buffer_pool,
+            consumer_shutdown,
+            sample_rate,
+            record_path,
+            format,
+        )
+    });
+
+    ctrlc::set_handler(move || {
+        let _ = tx.send(());
+    })?;
+    rx.recv().ok(); // block until Ctrl-C or a JACK shutdown notification
+
+    drop(async_client);
+    shutdown.store(true, Ordering::SeqCst);
+    consumer_thread.join().ok();
     Ok(())
 }
 
+/// Reacts to the JACK client lifecycle: connects new matching ports to `sink` as they
+/// appear, and unblocks `run` when the server shuts down instead of leaving it hanging.
+struct Notifications {
+    sink_name: String,
+    connect_pattern: Option<Regex>,
+    shutdown_tx: std::sync::mpsc::Sender<()>,
+}
+
+impl Notifications {
+    fn new(
+        sink_name: String,
+        connect_pattern: Option<Regex>,
+        shutdown_tx: std::sync::mpsc::Sender<()>,
+    ) -> Self {
+        Notifications {
+            sink_name,
+            connect_pattern,
+            shutdown_tx,
+        }
+    }
+}
+
+impl NotificationHandler for Notifications {
+    fn ports_registered(&mut self, client: &Client, _port_id: PortId) {
+        if let Some(pattern) = &self.connect_pattern {
+            connect::connect_matching(client, &self.sink_name, pattern);
+        }
+    }
+
+    fn shutdown(&mut self, status: ClientStatus, reason: &str) {
+        log::warn!("jack server is shutting down ({:?}): {}", status, reason);
+        let _ = self.shutdown_tx.send(());
+    }
+}
+
+/// Runs on a dedicated thread, parsing and logging the events that [`Ports::process`] copies
+/// off the realtime thread, so logging and `nom_midi` parsing never happen in the JACK
+/// callback. If `record_path` is set, also accumulates events into a [`Recorder`] and
+/// writes it out as a Standard MIDI File once `shutdown` is set and the queue is drained.
+fn consume_events(
+    mut consumer: Consumer<ConsumerEvent>,
+    dropped: DroppedCounter,
+    sysex_stats: SysExStats,
+    mut sysex_buffer_pool: SysExBufferPool,
+    shutdown: Arc<AtomicBool>,
+    sample_rate: usize,
+    record_path: Option<PathBuf>,
+    format: OutputFormat,
+) {
+    let mut last_report = Instant::now();
+    let mut recorder = record_path
+        .is_some()
+        .then(|| Recorder::new(sample_rate, smf::DEFAULT_PPQN, smf::DEFAULT_TEMPO_US_PER_BEAT));
+    let mut stdout = io::stdout();
+
+    loop {
+        match consumer.pop() {
+            Ok(ConsumerEvent::Raw(event)) => {
+                log_event(&event);
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.push(event.time, event.bytes());
+                }
+                let bytes = event.bytes();
+                let record = Record {
+                    time: event.time,
+                    channel: filter::channel(bytes),
+                    kind: filter::classify(bytes, false),
+                    data: data_bytes(bytes),
+                    raw: bytes,
+                };
+                if let Err(err) = record.write(format, &mut stdout) {
+                    log::warn!("failed to write structured output: {}", err);
+                }
+            }
+            Ok(ConsumerEvent::SysEx { time, message }) => {
+                log_sysex(time, &message);
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.push(time, &message);
+                }
+                let record = Record {
+                    time,
+                    channel: None,
+                    kind: filter::MessageKind::SysEx,
+                    data: sysex_payload(&message),
+                    raw: &message,
+                };
+                if let Err(err) = record.write(format, &mut stdout) {
+                    log::warn!("failed to write structured output: {}", err);
+                }
+                sysex_buffer_pool.recycle(message);
+            }
+            Err(PopError::Empty) => {
+                if shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        if last_report.elapsed() >= DROP_REPORT_INTERVAL {
+            let count = dropped.take();
+            if count > 0 {
+                log::warn!("dropped {} midi event(s), ring buffer was full", count);
+            }
+            let (interrupted, overflowed) = sysex_stats.take();
+            if interrupted > 0 {
+                log::warn!(
+                    "{} sysex message(s) interrupted by another status byte before completion",
+                    interrupted
+                );
+            }
+            if overflowed > 0 {
+                log::warn!(
+                    "{} sysex message(s) abandoned (exceeded {} bytes, or no spare buffer was available)",
+                    overflowed,
+                    MAX_SYSEX_BYTES
+                );
+            }
+            last_report = Instant::now();
+        }
+    }
+
+    if let (Some(recorder), Some(path)) = (recorder, record_path) {
+        match std::fs::File::create(&path).and_then(|file| recorder.finish(file)) {
+            Ok(()) => log::info!("wrote recording to {}", path.display()),
+            Err(err) => log::error!("failed to write recording to {}: {}", path.display(), err),
+        }
+    }
+}
+
+/// An event's bytes, minus its leading status byte (for structured output's `data`
+/// field). Empty for a status-only message like MIDI clock.
+fn data_bytes(bytes: &[u8]) -> &[u8] {
+    bytes.get(1..).unwrap_or(&[])
+}
+
+/// A reassembled SysEx message's payload, with the framing `0xF0`/`0xF7` stripped.
+fn sysex_payload(message: &[u8]) -> &[u8] {
+    let end = message.len().saturating_sub(1).max(1);
+    message.get(1..end).unwrap_or(&[])
+}
+
+fn log_event(event: &RawEvent) {
+    match nom_midi::parser::parse_midi_event(event.bytes()) {
+        Ok((_, evt)) => {
+            log::info!(
+                "received {:?} at {} (raw: {:?})",
+                evt,
+                event.time,
+                RawMidi(event.bytes())
+            );
+        }
+        Err(_) => {
+            log::info!(
+                "unparseable midi event {:?} at {}",
+                RawMidi(event.bytes()),
+                event.time
+            );
+        }
+    }
+}
+
+fn log_sysex(time: u32, message: &[u8]) {
+    match sysex::manufacturer_id(message) {
+        Some(id) => log::info!(
+            "received sysex from manufacturer {:?} at {} (raw: {:?})",
+            RawMidi(id),
+            time,
+            RawMidi(message)
+        ),
+        None => log::info!("received (too short to identify) sysex at {} (raw: {:?})", time, RawMidi(message)),
+    }
+}
+
 struct Ports {
     sink: Port<MidiIn>,
+    /// Present only when `--forward` was passed; events that pass `filter` are copied
+    /// out through it in the same process cycle they were received.
+    thru: Option<Port<MidiOut>>,
+    filter: Filter,
+    producer: Producer<ConsumerEvent>,
+    dropped: DroppedCounter,
+    sysex: SysExAccumulator,
 }
 
 impl Ports {
     /// Our constructor. Here we setup the ports we want and store them in our jack state object.
-    fn setup(client: &Client) -> Result<Self, Box<dyn StdError>> {
+    fn setup(
+        client: &Client,
+        producer: Producer<ConsumerEvent>,
+        dropped: DroppedCounter,
+        sysex_stats: SysExStats,
+        sysex_free_buffers: Consumer<Vec<u8>>,
+        forward: bool,
+        filter: Filter,
+    ) -> Result<Self, Box<dyn StdError>> {
         let sink = client.register_port("sink", MidiIn)?;
+        let thru = if forward {
+            Some(client.register_port("thru", MidiOut)?)
+        } else {
+            None
+        };
 
-        Ok(Ports { sink })
+        Ok(Ports {
+            sink,
+            thru,
+            filter,
+            producer,
+            dropped,
+            sysex: SysExAccumulator::new(MAX_SYSEX_BYTES, sysex_stats, sysex_free_buffers),
+        })
+    }
+
+    /// Hand a regular (non-SysEx) event off to the consumer thread.
+    ///
+    /// Bytes with no leading status byte aren't a standalone event on their own — they're
+    /// SysEx continuation or terminator bytes that arrived with no message in progress
+    /// (already counted via `SysExStats` by whichever `sysex.push` call produced them).
+    /// Forwarding them as a headerless `ConsumerEvent::Raw` would corrupt a recording, so
+    /// they're dropped here instead.
+    fn send_raw(&mut self, time: u32, bytes: &[u8]) {
+        if bytes.first().map_or(true, |&b| b < 0x80 || b == 0xF7) {
+            return;
+        }
+        match RawEvent::from_bytes(time, bytes) {
+            Some(event) => {
+                if self.producer.push(ConsumerEvent::Raw(event)).is_err() {
+                    self.dropped.increment();
+                }
+            }
+            None => self.dropped.increment(),
+        }
     }
 }
 
 impl ProcessHandler for Ports {
     fn process(&mut self, _client: &Client, process_scope: &ProcessScope) -> Control {
-        // process midi
+        let mut thru_writer = self.thru.as_mut().map(|port| port.writer(process_scope));
+
+        // Copy each event into the ring buffer; all parsing and logging happens on the
+        // consumer thread so this callback never allocates or blocks. SysEx messages
+        // that JACK split across several buffers are reassembled here first.
         for raw_midi in self.sink.iter(process_scope) {
-            match nom_midi::parser::parse_midi_event(raw_midi.bytes) {
-                Ok((_, evt)) => {
-                    log::info!("received {:?} at {} (raw: {:?})", evt, raw_midi.time, RawMidi(&raw_midi.bytes));
+            // Absolute frame time, so the consumer thread can timestamp events for
+            // recording without needing to see JACK's process cycles itself. Kept
+            // separate from `raw_midi.time` below, which `MidiWriter::write` needs as an
+            // offset relative to the current cycle, not an absolute frame count.
+            let abs_time = process_scope.last_frame_time().wrapping_add(raw_midi.time);
+            let bytes = raw_midi.bytes;
+            // Captured before `sysex.push` below, which may flip this back to `false`
+            // once the message completes or is abandoned.
+            let in_sysex = self.sysex.in_progress();
+
+            if let Some(writer) = thru_writer.as_mut() {
+                if self
+                    .filter
+                    .allows(filter::classify(bytes, in_sysex), filter::channel(bytes))
+                {
+                    let _ = writer.write(&JackRawMidi {
+                        time: raw_midi.time,
+                        bytes,
+                    });
+                }
+            }
+
+            match self.sysex.push(bytes) {
+                SysExOutcome::Complete(message) => {
+                    if self
+                        .producer
+                        .push(ConsumerEvent::SysEx {
+                            time: abs_time,
+                            message,
+                        })
+                        .is_err()
+                    {
+                        self.dropped.increment();
+                    }
+                }
+                // An overflowing event was part of the message that was just discarded,
+                // so it is dropped along with it.
+                SysExOutcome::Buffering | SysExOutcome::Overflow => {}
+                SysExOutcome::RealTimePassthrough | SysExOutcome::NotSysEx => {
+                    self.send_raw(abs_time, bytes);
                 }
-                Err(_) => {
-                    log::info!(
-                        "unparseable midi event {:?} at {}",
-                        RawMidi(&raw_midi.bytes),
-                        raw_midi.time
-                    );
+                SysExOutcome::Interrupted => {
+                    // The event that interrupted the in-progress message still needs
+                    // handling; re-feed it now that the accumulator is clear.
+                    match self.sysex.push(bytes) {
+                        SysExOutcome::Buffering | SysExOutcome::Overflow => {}
+                        SysExOutcome::Complete(message) => {
+                            if self
+                                .producer
+                                .push(ConsumerEvent::SysEx {
+                                    time: abs_time,
+                                    message,
+                                })
+                                .is_err()
+                            {
+                                self.dropped.increment();
+                            }
+                        }
+                        _ => self.send_raw(abs_time, bytes),
+                    }
                 }
             }
         }
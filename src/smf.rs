@@ -0,0 +1,226 @@
+use std::io::{self, Write};
+
+/// Default ticks (pulses) per quarter note for recordings.
+pub const DEFAULT_PPQN: u16 = 480;
+/// Default tempo, 120 BPM, expressed as microseconds per quarter note.
+pub const DEFAULT_TEMPO_US_PER_BEAT: u32 = 500_000;
+
+/// Accumulates `(frame, bytes)` pairs handed to it by the consumer thread and turns them
+/// into a type-1 Standard MIDI File on [`Recorder::finish`].
+///
+/// All the timestamping math happens here, off the realtime thread: the JACK callback
+/// only ever hands over a frame number and the raw event bytes.
+pub struct Recorder {
+    sample_rate: f64,
+    ppqn: u16,
+    tempo_us_per_beat: u32,
+    last_frame: Option<u32>,
+    events: Vec<(u32, Vec<u8>)>,
+}
+
+impl Recorder {
+    pub fn new(sample_rate: usize, ppqn: u16, tempo_us_per_beat: u32) -> Self {
+        Recorder {
+            sample_rate: sample_rate as f64,
+            ppqn,
+            tempo_us_per_beat,
+            last_frame: None,
+            events: Vec::new(),
+        }
+    }
+
+    /// Record one event, given its absolute JACK frame time.
+    pub fn push(&mut self, frame: u32, bytes: &[u8]) {
+        let delta_frames = match self.last_frame {
+            Some(prev) => frame.wrapping_sub(prev),
+            None => 0,
+        };
+        self.last_frame = Some(frame);
+        self.events.push((self.frames_to_ticks(delta_frames), bytes.to_vec()));
+    }
+
+    fn frames_to_ticks(&self, frames: u32) -> u32 {
+        let ticks_per_second = self.ppqn as f64 * 1_000_000.0 / self.tempo_us_per_beat as f64;
+        ((frames as f64 / self.sample_rate) * ticks_per_second).round() as u32
+    }
+
+    /// Write the accumulated events as a type-1 Standard MIDI File with a single track.
+    pub fn finish<W: Write>(&self, mut out: W) -> io::Result<()> {
+        out.write_all(b"MThd")?;
+        out.write_all(&6u32.to_be_bytes())?;
+        out.write_all(&1u16.to_be_bytes())?; // format 1
+        out.write_all(&1u16.to_be_bytes())?; // ntrks
+        out.write_all(&self.ppqn.to_be_bytes())?;
+
+        let mut track = Vec::new();
+        write_var_len(&mut track, 0);
+        write_tempo_meta(&mut track, self.tempo_us_per_beat);
+
+        let mut running_status = None;
+        for (delta, bytes) in &self.events {
+            write_var_len(&mut track, *delta);
+            write_event(&mut track, bytes, &mut running_status);
+        }
+
+        write_var_len(&mut track, 0);
+        track.extend_from_slice(&[0xFF, 0x2F, 0x00]); // end of track
+
+        out.write_all(b"MTrk")?;
+        out.write_all(&(track.len() as u32).to_be_bytes())?;
+        out.write_all(&track)?;
+        Ok(())
+    }
+}
+
+fn write_tempo_meta(track: &mut Vec<u8>, tempo_us_per_beat: u32) {
+    let bytes = tempo_us_per_beat.to_be_bytes();
+    track.extend_from_slice(&[0xFF, 0x51, 0x03, bytes[1], bytes[2], bytes[3]]);
+}
+
+/// Write `value` as a standard MIDI variable-length quantity (big-endian, 7 bits per
+/// byte, high bit set on every byte but the last).
+fn write_var_len(out: &mut Vec<u8>, value: u32) {
+    let mut buffer = [0u8; 5];
+    let mut count = 0;
+    let mut value = value & 0x0FFF_FFFF;
+    loop {
+        buffer[count] = (value & 0x7F) as u8;
+        value >>= 7;
+        count += 1;
+        if value == 0 {
+            break;
+        }
+    }
+    for i in (0..count).rev() {
+        let mut byte = buffer[i];
+        if i != 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+    }
+}
+
+/// Write one event's bytes, applying running status: if `bytes` starts with the same
+/// channel-voice status byte as the previous event, the status byte is omitted.
+fn write_event(track: &mut Vec<u8>, bytes: &[u8], running_status: &mut Option<u8>) {
+    let status = match bytes.first() {
+        Some(&b) if b >= 0x80 => b,
+        _ => {
+            // Not a well-formed event (no status byte); write it verbatim and give up
+            // on running status for whatever follows.
+            track.extend_from_slice(bytes);
+            *running_status = None;
+            return;
+        }
+    };
+
+    if status == 0xF0 {
+        // SysEx: length-prefixed, and doesn't participate in running status.
+        *running_status = None;
+        track.push(0xF0);
+        write_var_len(track, bytes.len().saturating_sub(1) as u32);
+        track.extend_from_slice(&bytes[1..]);
+        return;
+    }
+
+    if status < 0xF0 && *running_status == Some(status) {
+        track.extend_from_slice(&bytes[1..]);
+    } else {
+        track.extend_from_slice(bytes);
+        *running_status = if status < 0xF0 { Some(status) } else { None };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn var_len_encodes_single_byte_values() {
+        let mut out = Vec::new();
+        write_var_len(&mut out, 0x00);
+        write_var_len(&mut out, 0x40);
+        write_var_len(&mut out, 0x7f);
+        assert_eq!(out, [0x00, 0x40, 0x7f]);
+    }
+
+    #[test]
+    fn var_len_encodes_multi_byte_values() {
+        let mut out = Vec::new();
+        write_var_len(&mut out, 0x80);
+        assert_eq!(out, [0x81, 0x00]);
+
+        let mut out = Vec::new();
+        write_var_len(&mut out, 0x3FFF);
+        assert_eq!(out, [0xFF, 0x7F]);
+
+        let mut out = Vec::new();
+        write_var_len(&mut out, 0x1FFFFF);
+        assert_eq!(out, [0xFF, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn event_with_new_status_sets_running_status() {
+        let mut track = Vec::new();
+        let mut running_status = None;
+        write_event(&mut track, &[0x90, 0x40, 0x7f], &mut running_status);
+        assert_eq!(track, [0x90, 0x40, 0x7f]);
+        assert_eq!(running_status, Some(0x90));
+    }
+
+    #[test]
+    fn repeated_status_omits_it_via_running_status() {
+        let mut track = Vec::new();
+        let mut running_status = Some(0x90);
+        write_event(&mut track, &[0x90, 0x41, 0x7f], &mut running_status);
+        assert_eq!(track, [0x41, 0x7f]);
+        assert_eq!(running_status, Some(0x90));
+    }
+
+    #[test]
+    fn different_status_breaks_running_status() {
+        let mut track = Vec::new();
+        let mut running_status = Some(0x90);
+        write_event(&mut track, &[0x80, 0x41, 0x40], &mut running_status);
+        assert_eq!(track, [0x80, 0x41, 0x40]);
+        assert_eq!(running_status, Some(0x80));
+    }
+
+    #[test]
+    fn sysex_is_length_prefixed_and_clears_running_status() {
+        let mut track = Vec::new();
+        let mut running_status = Some(0x90);
+        write_event(&mut track, &[0xF0, 0x41, 0x10, 0xF7], &mut running_status);
+        assert_eq!(track, [0xF0, 0x03, 0x41, 0x10, 0xF7]);
+        assert_eq!(running_status, None);
+    }
+
+    #[test]
+    fn event_with_no_status_byte_clears_running_status() {
+        let mut track = Vec::new();
+        let mut running_status = Some(0x90);
+        write_event(&mut track, &[0x10, 0x20], &mut running_status);
+        assert_eq!(track, [0x10, 0x20]);
+        assert_eq!(running_status, None);
+    }
+
+    #[test]
+    fn finish_produces_a_well_formed_header_and_track_chunk() {
+        let mut recorder = Recorder::new(48_000, DEFAULT_PPQN, DEFAULT_TEMPO_US_PER_BEAT);
+        recorder.push(0, &[0x90, 0x40, 0x7f]);
+        recorder.push(48_000, &[0x80, 0x40, 0x40]);
+
+        let mut out = Vec::new();
+        recorder.finish(&mut out).unwrap();
+
+        assert_eq!(&out[0..4], b"MThd");
+        assert_eq!(&out[4..8], &6u32.to_be_bytes());
+        assert_eq!(&out[8..10], &1u16.to_be_bytes());
+        assert_eq!(&out[10..12], &1u16.to_be_bytes());
+        assert_eq!(&out[12..14], &DEFAULT_PPQN.to_be_bytes());
+        assert_eq!(&out[14..18], b"MTrk");
+        let track_len = u32::from_be_bytes(out[18..22].try_into().unwrap());
+        assert_eq!(out.len(), 22 + track_len as usize);
+        assert_eq!(&out[out.len() - 4..], &[0x00, 0xFF, 0x2F, 0x00]);
+    }
+}
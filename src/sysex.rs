@@ -0,0 +1,350 @@
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// What happened after feeding an event to a [`SysExAccumulator`].
+#[derive(Debug)]
+pub enum SysExOutcome {
+    /// The event extended an in-progress SysEx message that isn't finished yet.
+    Buffering,
+    /// `0xF7` was seen; the full message (including the leading `0xF0` and trailing
+    /// `0xF7`) is ready.
+    Complete(Vec<u8>),
+    /// A System Real-Time status byte (`0xF8..=0xFF`) arrived while a SysEx message was
+    /// in progress. These may legally interleave with a SysEx stream, so the
+    /// in-progress message is left untouched and the caller should handle this event on
+    /// its own.
+    RealTimePassthrough,
+    /// A non-continuation status byte arrived before the in-progress message saw its
+    /// `0xF7`, so the partial message was discarded. The caller should handle this
+    /// event on its own (it may itself start a new SysEx message). Counted in
+    /// [`SysExStats`]; logging it is the consumer thread's job, not the realtime
+    /// callback's.
+    Interrupted,
+    /// Either the in-progress message grew past the accumulator's `max_bytes` guard, or
+    /// it completed but no recycled buffer was available from the [`SysExBufferPool`] to
+    /// hand it off without allocating. Either way the message was discarded, and this
+    /// event with it: the event was part of that message, so the caller should drop it.
+    Overflow,
+    /// Nothing to do with SysEx reassembly; the caller should handle this event as a
+    /// normal, standalone event.
+    NotSysEx,
+}
+
+/// Counts of abandoned SysEx messages, shared between the realtime thread (which only
+/// ever increments them) and the consumer thread (which periodically takes and logs
+/// them), the same way [`crate::event::DroppedCounter`] reports ring buffer overflows.
+#[derive(Clone)]
+pub struct SysExStats(Arc<SysExStatsInner>);
+
+struct SysExStatsInner {
+    interrupted: AtomicU64,
+    overflowed: AtomicU64,
+}
+
+impl SysExStats {
+    pub fn new() -> Self {
+        SysExStats(Arc::new(SysExStatsInner {
+            interrupted: AtomicU64::new(0),
+            overflowed: AtomicU64::new(0),
+        }))
+    }
+
+    fn record_interrupted(&self) {
+        self.0.interrupted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_overflow(&self) {
+        self.0.overflowed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Take the current `(interrupted, overflowed)` counts and reset them to zero.
+    pub fn take(&self) -> (u64, u64) {
+        (
+            self.0.interrupted.swap(0, Ordering::Relaxed),
+            self.0.overflowed.swap(0, Ordering::Relaxed),
+        )
+    }
+}
+
+/// A pool of preallocated SysEx message buffers, recycled between the consumer thread
+/// (which returns a buffer once it's done with a completed message) and the
+/// [`SysExAccumulator`] on the realtime thread (which only ever pops from the pool,
+/// never allocates).
+///
+/// Built once, off the realtime thread, by [`sysex_buffer_pool`].
+pub struct SysExBufferPool(Producer<Vec<u8>>);
+
+impl SysExBufferPool {
+    /// Recycle a buffer once the consumer thread is finished with it. If the pool is
+    /// already full (the accumulator hasn't needed to borrow from it yet) the buffer is
+    /// simply dropped, off the realtime thread, which is harmless.
+    pub fn recycle(&mut self, mut buffer: Vec<u8>) {
+        buffer.clear();
+        let _ = self.0.push(buffer);
+    }
+}
+
+/// Build a [`SysExBufferPool`] and the matching [`Consumer`] half handed to a
+/// [`SysExAccumulator`], seeded with `capacity` buffers of `max_bytes` capacity each.
+/// Call this on startup, off the realtime thread; the capacity-`max_bytes` allocations
+/// happen here, not in the accumulator.
+pub fn sysex_buffer_pool(capacity: usize, max_bytes: usize) -> (SysExBufferPool, Consumer<Vec<u8>>) {
+    let (mut producer, consumer) = RingBuffer::new(capacity);
+    for _ in 0..capacity {
+        let _ = producer.push(Vec::with_capacity(max_bytes));
+    }
+    (SysExBufferPool(producer), consumer)
+}
+
+/// Reassembles System Exclusive messages that JACK may split across several `process`
+/// cycles, one raw event per buffer.
+///
+/// The accumulation buffer is allocated once, sized to `max_bytes`, and never dropped or
+/// reallocated: completing a message hands it off by swapping in a spare buffer borrowed
+/// from a [`SysExBufferPool`] (built with [`sysex_buffer_pool`]) rather than cloning it,
+/// so reassembling a message never allocates on the realtime thread.
+pub struct SysExAccumulator {
+    max_bytes: usize,
+    buffer: Vec<u8>,
+    active: bool,
+    stats: SysExStats,
+    free_buffers: Consumer<Vec<u8>>,
+}
+
+impl SysExAccumulator {
+    /// `max_bytes` bounds how large a single reassembled message is allowed to get
+    /// before it is abandoned, so a runaway or malformed stream can't grow without
+    /// limit. `free_buffers` is the consuming half of a [`SysExBufferPool`], used to
+    /// borrow a spare buffer each time a message completes.
+    pub fn new(max_bytes: usize, stats: SysExStats, free_buffers: Consumer<Vec<u8>>) -> Self {
+        SysExAccumulator {
+            max_bytes,
+            buffer: Vec::with_capacity(max_bytes),
+            active: false,
+            stats,
+            free_buffers,
+        }
+    }
+
+    /// Whether a SysEx message is currently being reassembled. Exposed so filtering can
+    /// recognise continuation bytes (which don't start with `0xF0` or any status byte)
+    /// as part of the SysEx stream too.
+    pub fn in_progress(&self) -> bool {
+        self.active
+    }
+
+    /// Feed one JACK raw MIDI event's bytes to the accumulator.
+    pub fn push(&mut self, bytes: &[u8]) -> SysExOutcome {
+        let first = match bytes.first() {
+            Some(&b) => b,
+            None => return SysExOutcome::NotSysEx,
+        };
+
+        if self.active {
+            if (0xF8..=0xFF).contains(&first) {
+                return SysExOutcome::RealTimePassthrough;
+            }
+            if first < 0x80 || first == 0xF7 {
+                return self.append(bytes);
+            }
+            // Any other status byte means the previous message was interrupted.
+            self.active = false;
+            self.buffer.clear();
+            self.stats.record_interrupted();
+            return SysExOutcome::Interrupted;
+        }
+
+        if first == 0xF0 {
+            self.buffer.clear();
+            self.active = true;
+            return self.append(bytes);
+        }
+
+        SysExOutcome::NotSysEx
+    }
+
+    fn append(&mut self, bytes: &[u8]) -> SysExOutcome {
+        if self.buffer.len() + bytes.len() > self.max_bytes {
+            self.active = false;
+            self.buffer.clear();
+            self.stats.record_overflow();
+            return SysExOutcome::Overflow;
+        }
+        self.buffer.extend_from_slice(bytes);
+        if bytes.contains(&0xF7) {
+            self.active = false;
+            return match self.free_buffers.pop() {
+                Ok(spare) => {
+                    let message = std::mem::replace(&mut self.buffer, spare);
+                    SysExOutcome::Complete(message)
+                }
+                Err(_) => {
+                    // No spare buffer available without allocating one; drop the
+                    // message rather than violate the realtime no-alloc guarantee.
+                    self.buffer.clear();
+                    self.stats.record_overflow();
+                    SysExOutcome::Overflow
+                }
+            };
+        }
+        SysExOutcome::Buffering
+    }
+}
+
+/// Extract the manufacturer ID from a complete SysEx message (including the leading
+/// `0xF0`), for tagging the logged hex dump. Returns `None` if the message is too short
+/// to contain one, including a degenerate `[0xF0, 0xF7]` empty message.
+pub fn manufacturer_id(message: &[u8]) -> Option<&[u8]> {
+    let id = message.get(1..)?;
+    let id = id.strip_suffix(&[0xF7]).unwrap_or(id);
+    if id.is_empty() {
+        return None;
+    }
+    if id.first() == Some(&0x00) {
+        id.get(..3)
+    } else {
+        id.get(..1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accumulator(max_bytes: usize) -> SysExAccumulator {
+        let (_pool, free_buffers) = sysex_buffer_pool(4, max_bytes);
+        SysExAccumulator::new(max_bytes, SysExStats::new(), free_buffers)
+    }
+
+    #[test]
+    fn single_event_message_completes() {
+        let mut acc = accumulator(64);
+        match acc.push(&[0xF0, 0x41, 0x10, 0xF7]) {
+            SysExOutcome::Complete(message) => assert_eq!(message, [0xF0, 0x41, 0x10, 0xF7]),
+            other => panic!("expected Complete, got {:?}", other),
+        }
+        assert!(!acc.in_progress());
+    }
+
+    #[test]
+    fn message_split_across_several_events() {
+        let mut acc = accumulator(64);
+        assert!(matches!(acc.push(&[0xF0, 0x41]), SysExOutcome::Buffering));
+        assert!(acc.in_progress());
+        assert!(matches!(acc.push(&[0x10, 0x20]), SysExOutcome::Buffering));
+        match acc.push(&[0x30, 0xF7]) {
+            SysExOutcome::Complete(message) => {
+                assert_eq!(message, [0xF0, 0x41, 0x10, 0x20, 0x30, 0xF7])
+            }
+            other => panic!("expected Complete, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn real_time_bytes_interleave_without_disturbing_the_message() {
+        let mut acc = accumulator(64);
+        acc.push(&[0xF0, 0x41]);
+        assert!(matches!(
+            acc.push(&[0xF8]),
+            SysExOutcome::RealTimePassthrough
+        ));
+        assert!(acc.in_progress());
+        match acc.push(&[0x10, 0xF7]) {
+            SysExOutcome::Complete(message) => assert_eq!(message, [0xF0, 0x41, 0x10, 0xF7]),
+            other => panic!("expected Complete, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn another_status_byte_interrupts_the_message() {
+        let mut acc = accumulator(64);
+        acc.push(&[0xF0, 0x41]);
+        assert!(matches!(acc.push(&[0x90, 0x40, 0x7f]), SysExOutcome::Interrupted));
+        assert!(!acc.in_progress());
+    }
+
+    #[test]
+    fn oversized_message_overflows() {
+        let mut acc = accumulator(4);
+        acc.push(&[0xF0, 0x41, 0x10]);
+        assert!(matches!(acc.push(&[0x20, 0x30, 0xF7]), SysExOutcome::Overflow));
+        assert!(!acc.in_progress());
+    }
+
+    #[test]
+    fn exhausted_buffer_pool_drops_the_message_instead_of_allocating() {
+        // Pool starts with exactly one spare buffer; the first completed message
+        // consumes it, leaving none for the second.
+        let (_pool, free_buffers) = sysex_buffer_pool(1, 64);
+        let mut acc = SysExAccumulator::new(64, SysExStats::new(), free_buffers);
+        assert!(matches!(
+            acc.push(&[0xF0, 0x41, 0xF7]),
+            SysExOutcome::Complete(_)
+        ));
+        assert!(matches!(
+            acc.push(&[0xF0, 0x42, 0xF7]),
+            SysExOutcome::Overflow
+        ));
+        assert!(!acc.in_progress());
+    }
+
+    #[test]
+    fn recycled_buffer_lets_a_later_message_complete() {
+        let (mut pool, free_buffers) = sysex_buffer_pool(1, 64);
+        let mut acc = SysExAccumulator::new(64, SysExStats::new(), free_buffers);
+        assert!(matches!(
+            acc.push(&[0xF0, 0x41, 0xF7]),
+            SysExOutcome::Complete(_)
+        ));
+        assert!(matches!(
+            acc.push(&[0xF0, 0x42, 0xF7]),
+            SysExOutcome::Overflow
+        ));
+        pool.recycle(Vec::with_capacity(64));
+        match acc.push(&[0xF0, 0x43, 0xF7]) {
+            SysExOutcome::Complete(message) => assert_eq!(message, [0xF0, 0x43, 0xF7]),
+            other => panic!("expected Complete, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn plain_bytes_with_no_message_in_progress_are_not_sysex() {
+        let mut acc = accumulator(64);
+        assert!(matches!(acc.push(&[0x90, 0x40, 0x7f]), SysExOutcome::NotSysEx));
+        assert!(matches!(acc.push(&[]), SysExOutcome::NotSysEx));
+    }
+
+    #[test]
+    fn stats_take_resets_counts() {
+        let stats = SysExStats::new();
+        stats.record_interrupted();
+        stats.record_overflow();
+        stats.record_overflow();
+        assert_eq!(stats.take(), (1, 2));
+        assert_eq!(stats.take(), (0, 0));
+    }
+
+    #[test]
+    fn manufacturer_id_three_byte_form() {
+        // 0x00 prefix means the next two bytes complete the manufacturer id.
+        let message = [0xF0, 0x00, 0x01, 0x02, 0x7f, 0xF7];
+        assert_eq!(manufacturer_id(&message), Some(&[0x00, 0x01, 0x02][..]));
+    }
+
+    #[test]
+    fn manufacturer_id_one_byte_form() {
+        let message = [0xF0, 0x41, 0x10, 0xF7];
+        assert_eq!(manufacturer_id(&message), Some(&[0x41][..]));
+    }
+
+    #[test]
+    fn manufacturer_id_none_for_degenerate_empty_message() {
+        assert_eq!(manufacturer_id(&[0xF0, 0xF7]), None);
+    }
+
+    #[test]
+    fn manufacturer_id_none_for_too_short_message() {
+        assert_eq!(manufacturer_id(&[0xF0]), None);
+    }
+}